@@ -0,0 +1,89 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use rustls::{ProtocolVersion, ServerName};
+
+/// Camouflage shape observed from a single handshake against a front, cheap enough to keep around
+/// and reuse for the next connection to the same [`ServerName`] instead of picking a fresh random
+/// shape every time.
+///
+/// This never avoids the round trip to the real camouflage server itself — on TLS 1.2,
+/// `Client::connect_with_early_data` always round-trips to inspect the cleartext
+/// Certificate/CertificateVerify, and on TLS 1.3 the round trip is still how the Noise `<- e, ee`
+/// pong arrives. All it holds is the number of middlebox-compat ApplicationData records written
+/// last time, which a repeat connection to the same front reuses; the lengths of those records
+/// are still drawn at random on every connection.
+#[derive(Debug, Clone)]
+pub struct CachedHandshake {
+    pub negotiated_version: ProtocolVersion,
+    /// Count of middlebox-compatibility ApplicationData records emitted last time, reused by the
+    /// next TLS 1.3 connection to the same front.
+    pub middlebox_compat_app_data_records: u8,
+}
+
+/// Pluggable store for [`CachedHandshake`]s, keyed by the camouflage [`ServerName`] being
+/// impersonated. Modeled on rustls's `StoresClientSessions`.
+pub trait CamouflageCache: std::fmt::Debug + Send + Sync {
+    fn get(&self, server_name: &ServerName) -> Option<CachedHandshake>;
+    fn put(&self, server_name: ServerName, handshake: CachedHandshake);
+}
+
+/// Default in-memory [`CamouflageCache`], bounded by both a TTL and a maximum entry count so
+/// stale or excess camouflage material is evicted rather than accumulating forever.
+#[derive(Debug)]
+pub struct MemoryCamouflageCache {
+    ttl: Duration,
+    capacity: usize,
+    entries: Mutex<HashMap<ServerName, (CachedHandshake, Instant)>>,
+}
+
+impl MemoryCamouflageCache {
+    pub fn new(ttl: Duration, capacity: usize) -> Self {
+        MemoryCamouflageCache {
+            ttl,
+            capacity,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl Default for MemoryCamouflageCache {
+    fn default() -> Self {
+        // 10 minutes comfortably outlives a single burst of reconnects while still picking up a
+        // front rotating its handshake shape in reasonable time
+        Self::new(Duration::from_secs(600), 1024)
+    }
+}
+
+impl CamouflageCache for MemoryCamouflageCache {
+    fn get(&self, server_name: &ServerName) -> Option<CachedHandshake> {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get(server_name) {
+            Some((handshake, inserted_at)) if inserted_at.elapsed() < self.ttl => {
+                Some(handshake.clone())
+            }
+            Some(_) => {
+                entries.remove(server_name);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn put(&self, server_name: ServerName, handshake: CachedHandshake) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= self.capacity && !entries.contains_key(&server_name) {
+            // capacity is a safety bound rather than a precise working set, so evicting the
+            // single oldest entry on overflow is good enough
+            if let Some(oldest) = entries
+                .iter()
+                .min_by_key(|(_, (_, inserted_at))| *inserted_at)
+                .map(|(k, _)| k.clone())
+            {
+                entries.remove(&oldest);
+            }
+        }
+        entries.insert(server_name, (handshake, Instant::now()));
+    }
+}
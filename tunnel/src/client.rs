@@ -14,20 +14,25 @@ use std::io::{self, Write};
 use std::mem::{self, MaybeUninit};
 use std::sync::Arc;
 
+use crate::cache::{CachedHandshake, CamouflageCache, MemoryCamouflageCache};
 use crate::common::NO_ELLIGATOR_WORKAROUND;
 use crate::totp::Totp;
 use crate::utils::{hmac, parse_tls_plain_message, u16_from_be_slice, Xor};
 use crate::FingerprintSpec;
 
-use crate::utils::{
-    get_server_tls_version, read_tls_message, NoCertificateVerification, TlsMessageExt,
-};
+use crate::utils::{get_server_tls_version, read_tls_message, TlsMessageExt};
+use crate::verify::{verifier_for, VerifyMode};
 
 use super::common::{
     derive_psk, SnowyStream, DEFAULT_ALPN_PROTOCOLS, MAXIMUM_CIPHERTEXT_LENGTH, NOISE_PARAMS,
     PSKLEN, TLS_RECORD_HEADER_LENGTH,
 };
 
+/// Maximum early-data payload [`Client::connect_with_early_data`] will seal, bounded by how much
+/// room is left in the ClientHello's session id (32 bytes) once the Noise ephemeral key (32
+/// bytes, carried in random) and the AEAD tag (16 bytes) are accounted for.
+const MAX_EARLY_DATA_LEN: usize = 16;
+
 /// Client with config to establish snowy tunnels with peer servers
 #[derive(Clone, Derivative)]
 #[derivative(Debug)]
@@ -39,7 +44,8 @@ pub struct Client {
     pub fingerprint_spec: Arc<FingerprintSpec>,
     pub totp: Totp,
     pub _curve_point_mask: [u8; 32],
-    // pub verify_tls: bool,
+    pub camouflage_cache: Arc<dyn CamouflageCache>,
+    pub verify_mode: VerifyMode,
 }
 
 impl Client {
@@ -57,13 +63,24 @@ impl Client {
         key: impl AsRef<[u8]>,
         server_name: ServerName,
         fingerprint_spec: FingerprintSpec,
+    ) -> Self {
+        Self::new_with_verify_mode(key, server_name, fingerprint_spec, VerifyMode::default())
+    }
+
+    /// Create a client with a pre-shared key, a server name for camouflage, a fingerprint
+    /// specification used to apply to TLS ClientHello, and a [`VerifyMode`] controlling how far
+    /// the camouflage server's certificate is checked before the Noise session is committed to it.
+    pub fn new_with_verify_mode(
+        key: impl AsRef<[u8]>,
+        server_name: ServerName,
+        fingerprint_spec: FingerprintSpec,
+        verify_mode: VerifyMode,
     ) -> Self {
         let key = key.as_ref();
 
-        // TODO: option for verifying camouflage cert
         let mut tlsconf = rustls::ClientConfig::builder()
             .with_safe_defaults()
-            .with_custom_certificate_verifier(Arc::new(NoCertificateVerification {}))
+            .with_custom_certificate_verifier(verifier_for(&verify_mode))
             .with_no_client_auth();
         if let Some(ref ja3) = fingerprint_spec.ja3 {
             // fingerprint_spec.alpn is effective iff alpn is set in ja3
@@ -88,25 +105,48 @@ impl Client {
             fingerprint_spec: Arc::new(fingerprint_spec),
             totp: Totp::new(key, 60, 2),
             _curve_point_mask: hmac(NO_ELLIGATOR_WORKAROUND, key),
+            camouflage_cache: Arc::new(MemoryCamouflageCache::default()),
+            verify_mode,
         }
     }
 
     /// Handshake with a peer server on the other end of the `TcpStream`
     #[inline(always)]
     pub async fn connect(&self, stream: TcpStream) -> io::Result<SnowyStream> {
-        self.connect_with_early_data(stream, [0u8; 16]).await
+        self.connect_with_early_data(stream, &[]).await
     }
 
-    /// Handshake with a peer server on the other end of the `TcpStream`, sending a early data
-    /// piggybacked by ClientHello
+    /// Handshake with a peer server on the other end of the `TcpStream`, sending up to
+    /// [`MAX_EARLY_DATA_LEN`] bytes of early data AEAD-sealed into the Noise `-> psk, e` flight
+    /// that's already piggybacked on the ClientHello's random/session id, so the bytes ride along
+    /// with the very first flight instead of costing an extra round trip once the tunnel is up.
+    ///
+    /// This has nothing to do with TLS 1.3 early data. Unlike it, PSK-0-RTT here has no forward
+    /// secrecy and the sealed flight is replayable within the `Totp` window it's signed against,
+    /// so callers that care about that should keep `early_data` limited to idempotent bytes.
     ///
-    /// The early data embeded covertly in ClientHello session id along with Noise handshake. And
-    /// it has nothing to do with TLS 1.3 early data.
+    /// `early_data` longer than [`MAX_EARLY_DATA_LEN`] is rejected rather than spilled onto the
+    /// wire unframed: the Noise message only has room inside the ClientHello's random (32 bytes,
+    /// already spent on the Noise ephemeral key) and session id (32 bytes) fields, so the sealed
+    /// payload plus its 16-byte AEAD tag must fit in those remaining 32 bytes.
     pub async fn connect_with_early_data(
         &self,
         mut stream: TcpStream,
-        early_data: [u8; 16],
+        early_data: &[u8],
     ) -> io::Result<SnowyStream> {
+        if early_data.len() > MAX_EARLY_DATA_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "early data of {} bytes exceeds the {} bytes the ClientHello random/session id can carry sealed",
+                    early_data.len(),
+                    MAX_EARLY_DATA_LEN
+                ),
+            ));
+        }
+        let mut early_data_padded = [0u8; MAX_EARLY_DATA_LEN];
+        early_data_padded[..early_data.len()].copy_from_slice(early_data);
+
         let mut initiator = snow::Builder::new(NOISE_PARAMS.clone())
             .psk(0, &self.key)
             .build_initiator()
@@ -115,7 +155,7 @@ impl Client {
         let mut ping = [0u8; 64];
         let time_token = self.totp.generate_current::<16>();
         initiator
-            .write_message(&early_data, &mut ping)
+            .write_message(&early_data_padded, &mut ping)
             .expect("Noise state valid");
         // Mask the curve point to avoid being distinguished. It is a temporary workaround.
         // We should have used Elligator. But there seems no working implementation in Rust for now.
@@ -177,9 +217,28 @@ impl Client {
                 // handshake procedures any more. Actually, even Server Hello can also be
                 // fabricated locally without be distinguished. Here the fingerprint in ServerHello
                 // is useful, though.
-                // TODO: Cache SH for latter use instead of request camouflage server every time.
-                // TODO: Send mibble box compatibility CCS and more ApplicationData frames, as
-                //   in typical TLS 1.3 handshake.
+                //
+                // The round trip to the real camouflage server itself can't be skipped (`stream`
+                // is the only path the Noise `<- e, ee` pong arrives on), but a `CamouflageCache`
+                // hit lets repeat connections to the same front reuse the number of
+                // middlebox-compat ApplicationData records written last time (their lengths are
+                // still drawn at random per connection).
+                let cached = self.camouflage_cache.get(&self.server_name);
+                let app_data_records = cached
+                    .as_ref()
+                    .filter(|c| c.negotiated_version == ProtocolVersion::TLSv1_3)
+                    .map(|c| c.middlebox_compat_app_data_records)
+                    .unwrap_or(self.fingerprint_spec.middlebox_compat_app_data_records);
+                if self.fingerprint_spec.middlebox_compat {
+                    write_middlebox_compat_records(&mut stream, app_data_records).await?;
+                }
+                self.camouflage_cache.put(
+                    self.server_name.clone(),
+                    CachedHandshake {
+                        negotiated_version: ProtocolVersion::TLSv1_3,
+                        middlebox_compat_app_data_records: app_data_records,
+                    },
+                );
 
                 // Noise: <- e, ee
                 read_tls_message(&mut stream, &mut buf)
@@ -288,6 +347,70 @@ impl Client {
     }
 }
 
+/// Emit a dummy ChangeCipherSpec followed by `app_data_records` padded ApplicationData records,
+/// mimicking the middlebox-compatibility mode real TLS 1.3 stacks use so the flow shape preceding
+/// the Noise `<- e, ee` pong matches a genuine handshake instead of jumping straight to it.
+async fn write_middlebox_compat_records(
+    stream: &mut TcpStream,
+    app_data_records: u8,
+) -> io::Result<()> {
+    trace!(app_data_records, "middlebox compat CCS + ApplicationData");
+    // ChangeCipherSpec: content type 0x14, legacy record version, 1-byte body of 0x01
+    stream.write_all(&[0x14, 0x03, 0x03, 0x00, 0x01, 0x01]).await?;
+    for _ in 0..app_data_records {
+        // typical TLS 1.3 handshake wraps the encrypted Finished (and any early app data) in an
+        // ApplicationData record of plausible, padded length
+        let len = rand::thread_rng().gen_range(32..256);
+        let mut record = vec![0u8; TLS_RECORD_HEADER_LENGTH + len];
+        record[0] = TlsContentType::ApplicationData.get_u8();
+        record[1..3].copy_from_slice(&[0x03, 0x03]);
+        record[3..5].copy_from_slice(&(len as u16).to_be_bytes());
+        rand::thread_rng().fill(&mut record[TLS_RECORD_HEADER_LENGTH..]);
+        stream.write_all(&record).await?;
+    }
+    Ok(())
+}
+
+/// Accumulates raw bytes read from the socket and carves out complete TLS records regardless of
+/// how the underlying reads happened to chunk them, modeled on rustls's own
+/// `MessageDeframer`/`HandshakeJoiner`. This replaces the fragile assumption that a single socket
+/// read yields exactly one whole record: real peers and middleboxes coalesce multiple handshake
+/// messages into one record, or fragment one across several, and any leftover bytes after a
+/// record (e.g. a coalesced ChangeCipherSpec + encrypted Finished) must be retained for the next
+/// iteration rather than lost or mis-parsed.
+struct RecordDeframer {
+    buf: Vec<u8>,
+}
+
+impl RecordDeframer {
+    fn new() -> Self {
+        RecordDeframer { buf: Vec::new() }
+    }
+
+    /// Return the next complete TLS record, reading more bytes from `stream` as needed and
+    /// keeping whatever follows it buffered for the next call.
+    async fn next_record(&mut self, stream: &mut TcpStream) -> io::Result<Vec<u8>> {
+        let mut chunk = [0u8; 4096];
+        loop {
+            if self.buf.len() >= TLS_RECORD_HEADER_LENGTH {
+                let record_len =
+                    TLS_RECORD_HEADER_LENGTH + u16_from_be_slice(&self.buf[3..5]) as usize;
+                if self.buf.len() >= record_len {
+                    return Ok(self.buf.drain(..record_len).collect());
+                }
+            }
+            let n = stream.read(&mut chunk).await?;
+            if n == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "peer closed connection mid TLS record",
+                ));
+            }
+            self.buf.extend_from_slice(&chunk[..n]);
+        }
+    }
+}
+
 async fn tls12_handshake(
     tlsconn: &mut RustlsClientConnection,
     stream: &mut TcpStream,
@@ -299,6 +422,7 @@ async fn tls12_handshake(
         buf.set_len(buf.capacity());
         mem::transmute(buf)
     };
+    let mut deframer = RecordDeframer::new();
     let mut seen_ccs = false;
     loop {
         match (tlsconn.wants_read(), tlsconn.wants_write()) {
@@ -320,26 +444,18 @@ async fn tls12_handshake(
             }
             (true, false) => {
                 // flow: client <- server
-                stream.read_exact(&mut buf[..5]).await?;
-                let len = u16_from_be_slice(&buf[3..5]) as usize;
-                stream.read_exact(&mut buf[5..5 + len]).await?;
+                let record = deframer.next_record(stream).await?;
                 trace!(
-                    protocol = u16_from_be_slice(&buf[1..3]),
-                    msglen = u16_from_be_slice(&buf[3..5]),
+                    protocol = u16_from_be_slice(&record[1..3]),
+                    msglen = u16_from_be_slice(&record[3..5]),
                     "tls handshake {} <= {}, type: {:?}",
                     stream.local_addr().unwrap(),
                     stream.peer_addr().unwrap(),
-                    TlsContentType::from(buf[0]),
+                    TlsContentType::from(record[0]),
                 );
-                let mut n = tlsconn
-                    .read_tls(&mut io::Cursor::new(&mut buf[..5 + len]))
-                    .unwrap();
-                if n < 5 + len {
-                    n += tlsconn
-                        .read_tls(&mut io::Cursor::new(&mut buf[n..5 + len]))
-                        .unwrap();
-                }
-                debug_assert_eq!(n, 5 + len);
+                let record_type = record[0];
+                let n = tlsconn.read_tls(&mut io::Cursor::new(&record)).unwrap();
+                debug_assert_eq!(n, record.len());
                 tlsconn.process_new_packets().map_err(|e| {
                     debug!(
                         "tls state error when handshaking {} <-> {}: {:?}",
@@ -352,17 +468,17 @@ async fn tls12_handshake(
                         format!("TLS handshake state: {}", e),
                     )
                 })?;
-                match TlsContentType::from(buf[0]) {
+                match TlsContentType::from(record_type) {
                     TlsContentType::ChangeCipherSpec => {
-                        seen_ccs = true;
                         // after server ChangeCipherSpec, the final Handshake Finished message is encrypted
-                        // so it can be used to carry other data
+                        // it can be used to carry other data
+                        seen_ccs = true;
                         if stop_after_server_ccs {
                             break;
                         }
                     }
                     _ => {
-                        debug_assert_eq!(buf[0], TlsContentType::Handshake.get_u8());
+                        debug_assert_eq!(record_type, TlsContentType::Handshake.get_u8());
                         // by default, handshake is done after the Handshake Finished message
                         if seen_ccs {
                             break;
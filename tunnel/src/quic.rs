@@ -0,0 +1,233 @@
+//! A QUIC-Initial camouflage transport, parallel to the TLS-over-TCP path in [`crate::client`].
+//!
+//! Many networks now let long-lived UDP/443 QUIC flows through more readily than long-lived
+//! TCP/443 flows, so this embeds the same Noise `-> psk, e` material used on the TCP path into a
+//! QUIC Initial packet's CRYPTO frame (a TLS ClientHello carried over QUIC, via rustls's `quic`
+//! module) and completes the Noise exchange over the resulting UDP association, handing back the
+//! same [`SnowyStream`] abstraction as [`crate::client::Client::connect`].
+//!
+//! This is a first cut covering the client-initiated happy path only: a single Initial packet
+//! carrying the whole (unfragmented) ClientHello, no Retry support, and no loss
+//! recovery/ACK-driven retransmission, matching how much of the TCP path's own TODOs are scoped
+//! incrementally rather than all at once. Initial packet protection (AEAD + header protection)
+//! is applied in full, though, since the Initial keys are publicly derivable from `dcid` alone
+//! (RFC 9001 section 5.2) and skipping it would produce a packet no conformant QUIC stack or
+//! QUIC-aware observer would accept as genuine.
+//!
+//! **Not end-to-end functional yet.** Nothing in this crate implements the server side of this
+//! transport (there is no UDP-listening counterpart to [`crate::server::Server`] anywhere in this
+//! tree), so [`QuicClient::connect`] has no real peer to complete the Noise `<- e, ee` exchange
+//! against. It is not wired into [`crate::pt`]. Treat this module as the client-side half of the
+//! wire format only, until a matching server acceptor exists.
+
+use rand::Rng;
+use rustls::quic::{ClientConnection as QuicClientConnection, Keys, Version};
+use rustls::ServerName;
+use tokio::net::UdpSocket;
+use tracing::trace;
+
+use std::io;
+use std::sync::Arc;
+
+use crate::totp::Totp;
+use crate::utils::{NoCertificateVerification, Xor};
+use crate::FingerprintSpec;
+
+use super::common::{derive_psk, SnowyStream, NOISE_PARAMS, PSKLEN};
+
+/// QUIC v1 (RFC 9000) wire version.
+const QUIC_VERSION_1: u32 = 0x0000_0001;
+/// Client Initial datagrams (and the first one in particular) must be padded to at least this
+/// many bytes to bound server amplification in response to a spoofed source address.
+const MIN_INITIAL_DATAGRAM_LEN: usize = 1200;
+
+/// Client for the QUIC-Initial camouflage transport. Mirrors [`crate::client::Client`] but speaks
+/// over a `UdpSocket` instead of a `TcpStream`.
+#[derive(Debug, Clone)]
+pub struct QuicClient {
+    pub key: [u8; PSKLEN],
+    pub server_name: ServerName,
+    pub fingerprint_spec: Arc<FingerprintSpec>,
+    pub totp: Totp,
+}
+
+impl QuicClient {
+    pub fn new(key: impl AsRef<[u8]>, server_name: ServerName) -> Self {
+        let key = key.as_ref();
+        QuicClient {
+            key: derive_psk(key),
+            server_name,
+            fingerprint_spec: Default::default(),
+            totp: Totp::new(key, 60, 2),
+        }
+    }
+
+    /// Handshake with a peer server over a connected `UdpSocket`, camouflaged as a QUIC Initial
+    /// handshake to the configured `server_name`.
+    pub async fn connect(&self, socket: UdpSocket) -> io::Result<SnowyStream> {
+        let mut initiator = snow::Builder::new(NOISE_PARAMS.clone())
+            .psk(0, &self.key)
+            .build_initiator()
+            .expect("Valid noise params");
+        // Noise: -> psk, e
+        let mut ping = [0u8; 64];
+        let time_token = self.totp.generate_current::<16>();
+        initiator
+            .write_message(&[0u8; 16], &mut ping)
+            .expect("Valid noise state");
+        (&mut ping[48..64]).xored(&time_token);
+        // `random`/`session_id` carry the whole `-> psk, e` message covertly, like the TCP path
+        // (see `crate::client::Client::connect_with_early_data`): `random` is the Noise
+        // ephemeral pubkey `e`, `session_id` is the (empty) sealed early-data payload plus AEAD
+        // tag, time-signed the same way for anti-replay. Unlike the TCP path, `e` is sent
+        // unmasked: no curve-point mask is applied here or to the pong read back below.
+        let random = <[u8; 32]>::try_from(&ping[0..32]).unwrap().into();
+        let session_id = <[u8; 32]>::try_from(&ping[32..64])
+            .unwrap()
+            .as_slice()
+            .into();
+
+        let dcid = {
+            let mut dcid = [0u8; 20];
+            rand::thread_rng().fill(&mut dcid);
+            dcid
+        };
+        let scid = {
+            let mut scid = [0u8; 8];
+            rand::thread_rng().fill(&mut scid);
+            scid
+        };
+
+        let mut tlsconf = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_custom_certificate_verifier(Arc::new(NoCertificateVerification {}))
+            .with_no_client_auth();
+        tlsconf.alpn_protocols = vec![b"h3".to_vec()];
+        let quic_params = vec![]; // TODO: shape transport parameters to match a real QUIC client
+
+        let chwriter = self
+            .fingerprint_spec
+            .get_client_hello_overwriter(true, true);
+        let mut quic_conn = QuicClientConnection::new_with(
+            Arc::new(tlsconf),
+            Version::V1,
+            self.server_name.clone(),
+            quic_params,
+            random,
+            Some(session_id),
+            chwriter,
+        )
+        .expect("Valid QUIC client config");
+
+        // The ClientHello (with the `e` pubkey riding covertly in its random/session id, via the
+        // same get_client_hello_overwriter plumbing as the TCP path) comes out of rustls with no
+        // record-layer framing, ready to be carried directly in a CRYPTO frame.
+        let mut crypto_data = Vec::new();
+        quic_conn.write_hs(&mut crypto_data);
+
+        let initial_keys = Keys::initial(Version::V1, &dcid, true);
+        let datagram = build_initial_packet(&dcid, &scid, &crypto_data, &initial_keys);
+
+        socket.send(&datagram).await?;
+        trace!(dcid = ?dcid, scid = ?scid, "sent QUIC Initial ClientHello");
+
+        // Noise: <- e, ee, carried as the payload of the association's next datagram once the
+        // server has relayed/fabricated the matching Initial response; unwrapping the genuine
+        // QUIC Initial/Handshake packet protection here is left for a follow-up, same as the
+        // TCP path originally shipped TLS 1.2 support before TLS 1.3 caching.
+        let mut pong = [0u8; 2048];
+        let len = socket.recv(&mut pong).await?;
+        if len < 48 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Noise handshake failed due to message length shorter than expected",
+            ));
+        }
+        let e_ee: [u8; 48] = pong[0..48].try_into().unwrap();
+        initiator
+            .read_message(&e_ee, &mut [])
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let noise = initiator
+            .into_transport_mode()
+            .expect("Noise handshake done");
+        Ok(SnowyStream::new_over_udp(socket, noise))
+    }
+}
+
+/// Assemble a single client Initial packet: a QUIC long header, a CRYPTO frame carrying
+/// `crypto_data`, PADDING up to [`MIN_INITIAL_DATAGRAM_LEN`], with Initial packet protection
+/// (AEAD + header protection) applied using the publicly-derivable Initial keys for `dcid`.
+///
+/// This covers the single-CRYPTO-frame, single-packet-number-space happy path only; splitting
+/// `crypto_data` across multiple Initial packets if it doesn't fit is not implemented.
+fn build_initial_packet(dcid: &[u8], scid: &[u8], crypto_data: &[u8], keys: &Keys) -> Vec<u8> {
+    let mut frame = Vec::new();
+    frame.push(0x06); // CRYPTO frame type
+    push_varint(&mut frame, 0); // offset
+    push_varint(&mut frame, crypto_data.len() as u64);
+    frame.extend_from_slice(crypto_data);
+
+    let packet_number: u16 = 0;
+    let tag_len = keys.local.packet.tag_len();
+
+    let mut header = Vec::new();
+    header.push(0xc1); // long header, fixed bit, Initial type, 2-byte packet number
+    header.extend_from_slice(&QUIC_VERSION_1.to_be_bytes());
+    header.push(dcid.len() as u8);
+    header.extend_from_slice(dcid);
+    header.push(scid.len() as u8);
+    header.extend_from_slice(scid);
+    push_varint(&mut header, 0); // token length (no retry token on the first Initial)
+    let unpadded_len = frame.len() + 2 /* packet number */ + tag_len;
+    let padding = MIN_INITIAL_DATAGRAM_LEN.saturating_sub(header.len() + 2 + unpadded_len);
+    push_varint(&mut header, (unpadded_len + padding) as u64);
+    let packet_number_offset = header.len();
+    header.extend_from_slice(&packet_number.to_be_bytes());
+
+    let mut payload = frame;
+    payload.resize(payload.len() + padding, 0); // PADDING frames are type 0x00
+
+    // Seal the payload in place with the Initial AEAD key, using the (unprotected) header as
+    // associated data, per RFC 9001 section 5.3.
+    let tag = keys
+        .local
+        .packet
+        .encrypt_in_place(packet_number as u64, &header, &mut payload)
+        .expect("sealing with the publicly-derivable Initial keys cannot fail");
+    payload.extend_from_slice(tag.as_ref());
+
+    let mut datagram = header;
+    datagram.extend_from_slice(&payload);
+
+    // Header-protect the first byte and the packet number using a sample taken 4 bytes past the
+    // start of the packet number field (so 2 bytes into the already AEAD-sealed payload here),
+    // per RFC 9001 section 5.4.
+    let sample_offset = packet_number_offset + 4;
+    let sample = &datagram[sample_offset..sample_offset + 16];
+    let mut first_byte = datagram[0];
+    let mut packet_number_bytes = [
+        datagram[packet_number_offset],
+        datagram[packet_number_offset + 1],
+    ];
+    keys.local
+        .header
+        .encrypt_in_place(sample, &mut first_byte, &mut packet_number_bytes)
+        .expect("header-protecting with the publicly-derivable Initial keys cannot fail");
+    datagram[0] = first_byte;
+    datagram[packet_number_offset..packet_number_offset + 2]
+        .copy_from_slice(&packet_number_bytes);
+
+    datagram
+}
+
+fn push_varint(buf: &mut Vec<u8>, v: u64) {
+    if v < 64 {
+        buf.push(v as u8);
+    } else if v < 16384 {
+        buf.extend_from_slice(&((v as u16) | 0x4000).to_be_bytes());
+    } else if v < 1073741824 {
+        buf.extend_from_slice(&((v as u32) | 0x8000_0000).to_be_bytes());
+    } else {
+        buf.extend_from_slice(&(v | 0xc000_0000_0000_0000).to_be_bytes());
+    }
+}
@@ -1,17 +1,23 @@
-use lru::LruCache;
 use rand::Rng;
 use rustls::{HandshakeType, ProtocolVersion};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::tcp::{ReadHalf, WriteHalf};
+use tokio::io::{self as tokio_io, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tokio::net::{TcpStream, ToSocketAddrs};
 use tracing::{debug, trace};
 
+use std::collections::HashMap;
 use std::fmt::Debug;
 use std::io;
 use std::mem;
 use std::net::SocketAddr;
 use std::sync::Mutex;
-
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// `SnowyStream` and `AcceptError` below are used generically (`SnowyStream<I>`,
+// `AcceptError<I>`) to let `Server::accept`/`accept_with_early_data` run over any
+// `AsyncRead + AsyncWrite` transport, not just `TcpStream`. That requires `common::SnowyStream`
+// itself to carry a matching type parameter (defaulted to `TcpStream` so the existing
+// non-generic call sites in `client.rs` keep compiling unchanged); this crate treats that as a
+// prerequisite change to `common.rs` rather than something this file can express on its own.
 use crate::common::{
     derive_psk, EarlyData, SnowyStream, NOISE_PARAMS, NO_ELLIGATOR_WORKAROUND, PSKLEN,
     TLS_RECORD_HEADER_LENGTH,
@@ -24,42 +30,175 @@ use crate::utils::{
 
 const SERVER_HELLO_RANDOM_START_INDEX: usize = TLS_RECORD_HEADER_LENGTH + 6;
 
+/// Must match the `period`/`skew` arguments `Totp::new` is constructed with below, so the
+/// generation bucketing in [`ReplayFilter`] lines up with the steps `generate_current_skewed`
+/// actually verifies against.
+const TOTP_PERIOD_SECS: i64 = 60;
+const TOTP_SKEW_STEPS: i64 = 2;
+
+fn current_totp_step() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system clock is before the epoch")
+        .as_secs() as i64
+        / TOTP_PERIOD_SECS
+}
+
+/// Anti-replay filter bucketed by Totp time step instead of bounded by a fixed capacity.
+///
+/// A capacity-bounded LRU can evict a still-valid client ephemeral nonce before its TOTP validity
+/// window expires under a flood of distinct authenticated connections, letting an attacker replay
+/// a captured ClientHello and get re-authenticated. Keeping one generation per time step instead
+/// bounds memory to however many connections were actually seen within the ~5-minute validity
+/// window, and guarantees every replay inside that window is detected regardless of traffic
+/// volume.
+#[derive(Debug, Default)]
+pub struct ReplayFilter {
+    generations: HashMap<i64, HashMap<[u8; 32], SocketAddr>>,
+}
+
+impl ReplayFilter {
+    fn new() -> Self {
+        Default::default()
+    }
+
+    /// Record `nonce` as seen from `from` in the generation for `step`, returning the address it
+    /// was first seen from if this is a replay within that same step.
+    fn check_and_insert(
+        &mut self,
+        step: i64,
+        nonce: [u8; 32],
+        from: SocketAddr,
+    ) -> Option<SocketAddr> {
+        let generation = self.generations.entry(step).or_default();
+        if let Some(&first_from) = generation.get(&nonce) {
+            return Some(first_from);
+        }
+        generation.insert(nonce, from);
+        None
+    }
+
+    /// Drop every generation older than `oldest_valid_step`, the oldest step still covered by the
+    /// current skewed validity window.
+    fn evict_expired(&mut self, oldest_valid_step: i64) {
+        self.generations.retain(|&step, _| step >= oldest_valid_step);
+    }
+}
+
+/// A SNI-glob/ALPN match rule used to pick a backend out of [`Server::routes`].
+///
+/// `sni_glob` supports a single leading `*` wildcard (e.g. `*.example.com`); `None` in either
+/// field matches anything for that dimension.
+#[derive(Debug, Clone)]
+pub struct CamouflageRoute {
+    pub sni_glob: Option<String>,
+    pub alpn: Option<Vec<u8>>,
+}
+
+impl CamouflageRoute {
+    fn matches(&self, sni: Option<&str>, alpn: &[Vec<u8>]) -> bool {
+        let sni_ok = match (&self.sni_glob, sni) {
+            (None, _) => true,
+            (Some(pattern), Some(name)) => glob_match(pattern, name),
+            (Some(_), None) => false,
+        };
+        let alpn_ok = match &self.alpn {
+            None => true,
+            Some(wanted) => alpn.iter().any(|p| p == wanted),
+        };
+        sni_ok && alpn_ok
+    }
+}
+
+fn glob_match(pattern: &str, value: &str) -> bool {
+    match pattern.strip_prefix('*') {
+        Some(suffix) => value.ends_with(suffix),
+        None => pattern == value,
+    }
+}
+
 /// Server with config to establish snowy tunnels with peer clients
 #[derive(Debug)]
 pub struct Server<A: ToSocketAddrs + Debug> {
     pub key: [u8; PSKLEN],
+    /// Default backend, used when no entry in `routes` matches the client's ClientHello.
     pub camouflage_addr: A,
-    pub replay_filter: Mutex<LruCache<[u8; 32], SocketAddr>>, // TODO: TOTP; prevent DoS attack
+    /// Additional backends, tried in order before falling back to `camouflage_addr`, so an
+    /// operator fronting several real sites can have the forwarded handshake reach whichever
+    /// backend the client's requested name actually belongs to.
+    pub routes: Vec<(CamouflageRoute, A)>,
+    pub replay_filter: Mutex<ReplayFilter>,
     pub totp: Totp,
     pub _curve_point_mask: [u8; 32],
+    /// How many middlebox-compatibility filler records (a ChangeCipherSpec followed by this many
+    /// ApplicationData records) a TLS 1.3 client configured with
+    /// `FingerprintSpec::middlebox_compat` writes right after it sees ServerHello, before reading
+    /// the Noise pong. `None` (the default) means clients aren't expected to send any. There is
+    /// no negotiation for this: it must be set to match whatever the peer `Client`s are
+    /// configured with, the same way `key` must.
+    pub middlebox_compat_app_data_records: Option<u8>,
 }
 
 impl<A: ToSocketAddrs + Debug> Server<A> {
-    /// Create a server with a pre-shared key, a camouflage server address, and a capacity of the
-    /// internal LRU-based replay filter queue.
+    /// Create a server with a pre-shared key and a camouflage server address.
     ///
     /// The camouflage server address is to where TLS handshakes from clients are forwarded and
     /// from where responses are forwarded backed to clients. Generally, it should match the server
     /// name specified in a tunnel's client-side.
-    pub fn new(key: impl AsRef<[u8]>, camouflage_addr: A, replay_filter_size: usize) -> Self {
+    pub fn new(key: impl AsRef<[u8]>, camouflage_addr: A) -> Self {
         let key = key.as_ref();
         Server {
             key: derive_psk(key),
             camouflage_addr,
-            replay_filter: Mutex::new(LruCache::new(replay_filter_size)),
-            totp: Totp::new(key, 60, 2),
+            routes: Vec::new(),
+            replay_filter: Mutex::new(ReplayFilter::new()),
+            totp: Totp::new(key, TOTP_PERIOD_SECS as u64, TOTP_SKEW_STEPS as usize),
             _curve_point_mask: hmac(NO_ELLIGATOR_WORKAROUND, key),
+            middlebox_compat_app_data_records: None,
         }
     }
 
-    /// Accept a incoming TcpStream as a [`SnowyStream`].
+    /// Add a SNI/ALPN-matched backend, tried before the default `camouflage_addr`.
+    pub fn with_route(mut self, route: CamouflageRoute, addr: A) -> Self {
+        self.routes.push((route, addr));
+        self
+    }
+
+    /// Configure how many middlebox-compatibility filler records to expect (and discard) from
+    /// TLS 1.3 clients right after ServerHello. Must match the `middlebox_compat`/
+    /// `middlebox_compat_app_data_records` the peer `Client`s are configured with.
+    pub fn with_middlebox_compat_app_data_records(mut self, app_data_records: u8) -> Self {
+        self.middlebox_compat_app_data_records = Some(app_data_records);
+        self
+    }
+
+    fn select_backend(
+        &self,
+        chp: &rustls::internal::msgs::handshake::ClientHelloPayload,
+    ) -> &A {
+        let sni = get_client_sni(chp);
+        let alpn = get_client_alpn(chp);
+        self.routes
+            .iter()
+            .find(|(route, _)| route.matches(sni.as_deref(), &alpn))
+            .map(|(_, addr)| addr)
+            .unwrap_or(&self.camouflage_addr)
+    }
+
+    /// Accept a incoming stream as a [`SnowyStream`].
     ///
     /// See [`accept_with_early_data`](#method.accept_with_early_data) for more info.
-    pub async fn accept(&self, inbound: TcpStream) -> Result<SnowyStream, AcceptError> {
-        self.accept_with_early_data(inbound).await.map(|(s, _d)| s)
+    pub async fn accept<I: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        inbound: I,
+        peer_addr: SocketAddr,
+    ) -> Result<SnowyStream<I>, AcceptError<I>> {
+        self.accept_with_early_data(inbound, peer_addr)
+            .await
+            .map(|(s, _d)| s)
     }
 
-    /// Accept a incoming TcpStream as a [`SnowyStream`].
+    /// Accept a incoming stream as a [`SnowyStream`].
     ///
     /// The server tries to authenticate a client by a Noise handshake message piggybacked by a TLS
     /// ClientHello (the first message in TLS handshakes). If the client is successfully
@@ -72,10 +211,18 @@ impl<A: ToSocketAddrs + Debug> Server<A> {
     /// [`AcceptError`]. The caller may decide to proceed to forward traffic between the client and
     /// the camouflage server on its own (falling back to dumb relay) or just reject/drop the
     /// connection.
-    pub async fn accept_with_early_data(
+    ///
+    /// `inbound` is generic over any `AsyncRead + AsyncWrite` transport (a bare `TcpStream`, a
+    /// pre-accepted TLS-terminated connection, an in-process duplex for testing, ...), so unlike a
+    /// `TcpStream` it has no address of its own to log or key the replay filter on; the caller
+    /// supplies `peer_addr` for that (typically `TcpStream::peer_addr()` when `inbound` is a real
+    /// socket). The camouflage-facing `outbound` leg stays a plain `TcpStream`, since it is always
+    /// this server dialing out to `camouflage_addr`/`routes`.
+    pub async fn accept_with_early_data<I: AsyncRead + AsyncWrite + Unpin>(
         &self,
-        mut inbound: TcpStream,
-    ) -> Result<(SnowyStream, EarlyData), AcceptError> {
+        mut inbound: I,
+        peer_addr: SocketAddr,
+    ) -> Result<(SnowyStream<I>, EarlyData), AcceptError<I>> {
         use AcceptError::*;
 
         let mut responder = snow::Builder::new(NOISE_PARAMS.clone())
@@ -91,10 +238,10 @@ impl<A: ToSocketAddrs + Debug> Server<A> {
 
         // Noise: -> psk, e
         let mut ping = [0u8; 64];
-        let chp = match read_tls_message(&mut inbound, &mut buf)
+        let chp = match reassemble_handshake_message(&mut inbound, &mut buf)
             .await?
             .ok()
-            .and_then(|_| parse_tls_plain_message(&buf).ok())
+            .and_then(|synthetic| parse_tls_plain_message(&synthetic).ok())
             .filter(|msg| msg.is_handshake_type(HandshakeType::ClientHello))
             .and_then(|msg| msg.into_client_hello_payload())
         {
@@ -113,51 +260,55 @@ impl<A: ToSocketAddrs + Debug> Server<A> {
             .unwrap_or(false);
         trace!(
             "client {} supports TLS 1.3: {}",
-            inbound.peer_addr().unwrap(),
+            peer_addr,
             client_tls1_3
         );
 
-        trace!("noise ping from {:?}, ping: {:x?}", &inbound, ping,);
+        trace!("noise ping from {:?}, ping: {:x?}", peer_addr, ping,);
         (&mut ping[..32]).xored(&self._curve_point_mask);
+        let now_step = current_totp_step();
         let mut early_data = [0u8; 16];
-        let mut verified = false;
-        for token in self.totp.generate_current_skewed::<16>() {
+        let mut verified_step = None;
+        for (offset, token) in self.totp.generate_current_skewed::<16>().into_iter().enumerate() {
             (&mut ping[48..64]).xored(&token);
             if responder.read_message(&ping, &mut early_data).is_ok() {
-                verified = true;
+                verified_step = Some(now_step - TOTP_SKEW_STEPS + offset as i64);
                 break;
             }
             (&mut ping[48..64]).xored(&token);
         }
-        if !verified {
-            return Err(Unauthenticated { buf, io: inbound });
-        }
-        debug!("authenticated {:?}", &inbound);
+        let step = match verified_step {
+            Some(step) => step,
+            None => return Err(Unauthenticated { buf, io: inbound, chp }),
+        };
+        debug!("authenticated {:?}", peer_addr);
         debug!("early_data: {:x?}", early_data);
         {
             let e = ping[..32].try_into().unwrap();
             let mut rf = self.replay_filter.lock().unwrap();
-            if let Some(&client_id) = rf.get(&e) {
+            if let Some(first_from) = rf.check_and_insert(step, e, peer_addr) {
                 return Err(ReplayDetected {
                     buf,
                     io: inbound,
                     nonce: e,
-                    first_from: client_id,
+                    first_from,
+                    chp,
                 });
             }
-            rf.put(e, inbound.peer_addr().unwrap());
+            rf.evict_expired(now_step - TOTP_SKEW_STEPS);
         }
 
-        let mut outbound = TcpStream::connect(&self.camouflage_addr).await?;
+        let backend = self.select_backend(&chp);
+        let mut outbound = TcpStream::connect(backend).await?;
 
         // forward Client Hello in whole to camouflage server
         outbound.write_all(&buf).await?;
 
         // read camouflage Server Hello back
-        let shp = match read_tls_message(&mut outbound, &mut buf)
+        let shp = match reassemble_handshake_message(&mut outbound, &mut buf)
             .await?
             .ok()
-            .and_then(|_| parse_tls_plain_message(&buf).ok())
+            .and_then(|synthetic| parse_tls_plain_message(&synthetic).ok())
             .filter(|msg| msg.is_handshake_type(HandshakeType::ServerHello))
             .and_then(|msg| msg.into_server_hello_payload())
         {
@@ -179,7 +330,7 @@ impl<A: ToSocketAddrs + Debug> Server<A> {
         trace!(
             // pad_len = pong.len() - (5 + 48),
             "e, ee to {:?}: {:x?}",
-            inbound,
+            peer_addr,
             &pong
         );
         (&mut pong[0..32]).xored(&self._curve_point_mask);
@@ -189,12 +340,27 @@ impl<A: ToSocketAddrs + Debug> Server<A> {
                 // TLS 1.3: handshake done
                 debug!(
                     "{} <-> {} negotiated TLS version: 1.3",
-                    inbound.peer_addr().unwrap(),
+                    peer_addr,
                     outbound.peer_addr().unwrap()
                 );
                 // forward camouflage server hello back to client
                 inbound.write_all(&buf).await?;
 
+                // a middlebox-compat-enabled client writes a dummy ChangeCipherSpec followed by
+                // `app_data_records` padded ApplicationData records right after ServerHello,
+                // before it reads the Noise pong; read and discard them here so they don't land
+                // unconsumed at the front of the Noise transport stream
+                if let Some(app_data_records) = self.middlebox_compat_app_data_records {
+                    read_tls_message(&mut inbound, &mut buf)
+                        .await?
+                        .expect("TODO"); // dummy ChangeCipherSpec
+                    for _ in 0..app_data_records {
+                        read_tls_message(&mut inbound, &mut buf)
+                            .await?
+                            .expect("TODO"); // dummy ApplicationData
+                    }
+                }
+
                 let len = rand::thread_rng().gen_range(108..908);
                 buf.reserve_exact(TLS_RECORD_HEADER_LENGTH + len);
                 unsafe { buf.set_len(TLS_RECORD_HEADER_LENGTH + len) };
@@ -209,7 +375,7 @@ impl<A: ToSocketAddrs + Debug> Server<A> {
                 // TLS 1.2: continue handshake
                 debug!(
                     "{} <-> {} negotiated TLS version: 1.2 or other",
-                    inbound.peer_addr().unwrap(),
+                    peer_addr,
                     outbound.peer_addr().unwrap()
                 );
                 if chp.session_id == shp.session_id {
@@ -233,7 +399,7 @@ impl<A: ToSocketAddrs + Debug> Server<A> {
                     inbound.write_all(&buf).await?;
                     debug!(
                         "{} <-> {} tls session resumed",
-                        inbound.peer_addr().unwrap(),
+                        peer_addr,
                         outbound.peer_addr().unwrap()
                     );
                 } else {
@@ -245,7 +411,7 @@ impl<A: ToSocketAddrs + Debug> Server<A> {
                     relay_until_tls12_handshake_finished(&mut inbound, &mut outbound).await?;
                     debug!(
                         "{} <-> {} tls full handshake done",
-                        inbound.peer_addr().unwrap(),
+                        peer_addr,
                         outbound.peer_addr().unwrap()
                     );
 
@@ -287,45 +453,212 @@ impl<A: ToSocketAddrs + Debug> Server<A> {
         let responder = responder
             .into_transport_mode()
             .expect("Noise handshake done");
-        trace!("noise handshake done with {:?}", inbound);
+        trace!("noise handshake done with {:?}", peer_addr);
         Ok((SnowyStream::new(inbound, responder), early_data))
     }
+
+    /// Accept a incoming stream, falling back to a transparent byte-for-byte relay against the
+    /// camouflage server whenever the client fails to authenticate, instead of just dropping the
+    /// connection.
+    ///
+    /// A dropped connection is itself a distinguisher: an active prober sees a server that
+    /// accepts bytes and then closes, unlike the real camouflage site. Splicing the client to the
+    /// camouflage backend (the matching entry in `routes` when the ClientHello parsed, otherwise
+    /// `camouflage_addr`) for the rest of the connection's lifetime on any [`AcceptError`] (other
+    /// than a bare I/O error, where there is no longer a usable socket to splice) makes a probe's
+    /// response indistinguishable from one against the genuine front. Returns `Ok(None)` once the
+    /// relay it fell back to has run to completion.
+    pub async fn accept_or_relay<I: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        inbound: I,
+        peer_addr: SocketAddr,
+    ) -> io::Result<Option<(SnowyStream<I>, EarlyData)>> {
+        use AcceptError::*;
+
+        match self.accept_with_early_data(inbound, peer_addr).await {
+            Ok((stream, early_data)) => Ok(Some((stream, early_data))),
+            Err(IoError(e)) => Err(e),
+            Err(ServerHelloInvalid {
+                buf,
+                mut inbound,
+                outbound,
+            }) => {
+                // the already-connected `outbound` already has the ClientHello forwarded and its
+                // (unparsable) response partially read into `buf`; replay that to the client
+                // before splicing the rest of the connection through
+                inbound.write_all(&buf).await?;
+                relay_until_closed(inbound, outbound).await?;
+                Ok(None)
+            }
+            Err(Unauthenticated { buf, io, chp }) | Err(ReplayDetected { buf, io, chp, .. }) => {
+                // the ClientHello parsed fine, so route the fallback relay the same way the
+                // authenticated path would, instead of always landing on the default backend
+                let backend = self.select_backend(&chp);
+                let mut outbound = TcpStream::connect(backend).await?;
+                outbound.write_all(&buf).await?;
+                relay_until_closed(io, outbound).await?;
+                Ok(None)
+            }
+            Err(ClientHelloInvalid { buf, io }) => {
+                // no ClientHello could be parsed at all, so there's no SNI/ALPN to route on;
+                // fall back to the default camouflage backend
+                let mut outbound = TcpStream::connect(&self.camouflage_addr).await?;
+                outbound.write_all(&buf).await?;
+                relay_until_closed(io, outbound).await?;
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// Bidirectionally copy raw bytes between `inbound` and `outbound` until either side closes,
+/// splicing a client transparently onto the camouflage server past the handshake.
+async fn relay_until_closed<I: AsyncRead + AsyncWrite + Unpin>(
+    mut inbound: I,
+    mut outbound: TcpStream,
+) -> io::Result<()> {
+    tokio_io::copy_bidirectional(&mut inbound, &mut outbound).await?;
+    Ok(())
 }
 
 /// Error returned by [`Server::accept`] with self-explanatory fields
-pub enum AcceptError {
+///
+/// Generic over the same inbound stream type `I` passed to [`Server::accept`], so the partially
+/// consumed connection it carries back can still be handed to a fallback relay (see
+/// [`Server::accept_or_relay`]) instead of being dropped along with the error.
+pub enum AcceptError<I> {
     IoError(io::Error),
     Unauthenticated {
         buf: Vec<u8>,
-        io: TcpStream,
+        io: I,
+        /// The ClientHello was still valid even though authentication failed, so the SNI/ALPN it
+        /// requested is known; [`Server::accept_or_relay`] uses this to pick the fallback relay
+        /// backend the same way the authenticated path does.
+        chp: rustls::internal::msgs::handshake::ClientHelloPayload,
     },
     ReplayDetected {
         buf: Vec<u8>,
-        io: TcpStream,
+        io: I,
         nonce: [u8; 32],
         first_from: SocketAddr,
+        /// See the `chp` field on [`AcceptError::Unauthenticated`].
+        chp: rustls::internal::msgs::handshake::ClientHelloPayload,
     },
     ClientHelloInvalid {
         buf: Vec<u8>,
-        io: TcpStream,
+        io: I,
     },
     ServerHelloInvalid {
         buf: Vec<u8>,
-        inbound: TcpStream,
+        inbound: I,
         outbound: TcpStream,
     },
 }
 
-impl From<io::Error> for AcceptError {
+impl<I> From<io::Error> for AcceptError<I> {
     fn from(err: io::Error) -> Self {
         Self::IoError(err)
     }
 }
 
+/// Extract the requested host name out of a ClientHello's SNI extension, if present.
+///
+/// Would naturally sit beside [`get_client_tls_versions`] in `utils`; kept local here since it's
+/// only needed for backend routing.
+fn get_client_sni(
+    chp: &rustls::internal::msgs::handshake::ClientHelloPayload,
+) -> Option<String> {
+    use rustls::internal::msgs::handshake::{ClientExtension, ServerNamePayload};
+
+    chp.extensions.iter().find_map(|ext| match ext {
+        ClientExtension::ServerName(names) => names.iter().find_map(|sni| match &sni.payload {
+            ServerNamePayload::HostName(name) => Some(name.as_ref().to_owned()),
+            _ => None,
+        }),
+        _ => None,
+    })
+}
+
+/// Extract the client's offered ALPN protocol list out of a ClientHello, if present.
+fn get_client_alpn(
+    chp: &rustls::internal::msgs::handshake::ClientHelloPayload,
+) -> Vec<Vec<u8>> {
+    use rustls::internal::msgs::handshake::ClientExtension;
+
+    chp.extensions
+        .iter()
+        .find_map(|ext| match ext {
+            ClientExtension::Protocols(protos) => {
+                Some(protos.iter().map(|p| p.as_ref().to_vec()).collect())
+            }
+            _ => None,
+        })
+        .unwrap_or_default()
+}
+
+/// Read content-type 0x16 (Handshake) TLS records from `stream` until exactly one complete
+/// handshake message (1-byte type + 3-byte length prefix) has arrived, regardless of whether the
+/// peer fragmented it across several records or coalesced it with other messages in one. Returns
+/// a synthetic single-record view of just that reassembled message for parsing, while replacing
+/// the contents of `raw` with every raw record byte read (including record headers, unmodified)
+/// so the exact bytes can still be forwarded to/from the camouflage server verbatim.
+///
+/// Trailing bytes belonging to a handshake message coalesced after the one we waited for are
+/// currently dropped from the parsed view (though still present, unmodified, in `raw`); this
+/// mirrors how this server only ever needs to authenticate a single ClientHello or ServerHello
+/// per connection.
+///
+/// `stream` is untrusted input (this is the first thing read off an inbound connection before any
+/// authentication happens), so the declared handshake body length is capped at
+/// [`MAX_HANDSHAKE_SIZE`]: without a cap, a peer could declare the maximum representable length
+/// (~16MB) and trickle it in slowly, tying up a connection and growing `hs` unboundedly.
+async fn reassemble_handshake_message<S: AsyncRead + Unpin>(
+    stream: &mut S,
+    raw: &mut Vec<u8>,
+) -> io::Result<Result<Vec<u8>, ()>> {
+    raw.clear();
+    let mut hs = Vec::new();
+    loop {
+        let mut record = Vec::new();
+        if read_tls_message(stream, &mut record).await?.is_err() {
+            return Ok(Err(()));
+        }
+        raw.extend_from_slice(&record);
+        if record.first().copied() != Some(0x16) {
+            return Ok(Err(()));
+        }
+        hs.extend_from_slice(&record[TLS_RECORD_HEADER_LENGTH..]);
+        if hs.len() >= 4 {
+            let body_len = ((hs[1] as usize) << 16) | ((hs[2] as usize) << 8) | hs[3] as usize;
+            if body_len > MAX_HANDSHAKE_SIZE {
+                return Ok(Err(()));
+            }
+            if hs.len() >= 4 + body_len {
+                hs.truncate(4 + body_len);
+                break;
+            }
+        }
+        if hs.len() > MAX_HANDSHAKE_SIZE {
+            return Ok(Err(()));
+        }
+    }
+    let mut synthetic = Vec::with_capacity(TLS_RECORD_HEADER_LENGTH + hs.len());
+    synthetic.extend_from_slice(&[0x16, 0x03, 0x03]);
+    synthetic.extend_from_slice(&(hs.len() as u16).to_be_bytes());
+    synthetic.extend_from_slice(&hs);
+    Ok(Ok(synthetic))
+}
+
+/// Upper bound on a single reassembled handshake message (e.g. a ClientHello or ServerHello),
+/// generous for any real one (the largest realistic ClientHello, padded to mimic a heavily
+/// fingerprinted browser, is still well under this) while bounding how much unauthenticated input
+/// [`reassemble_handshake_message`] will buffer per connection.
+const MAX_HANDSHAKE_SIZE: usize = 1 << 16;
+
 // Adapted from: https://github.com/ihciah/shadow-tls/blob/2bbdc26cff1120ba9c8eded39ad743c4c4f687c4/src/protocol.rs#L138
-async fn copy_until_tls12_handshake_finished<'a>(
-    mut read_half: ReadHalf<'a>,
-    mut write_half: WriteHalf<'a>,
+async fn copy_until_tls12_handshake_finished<R: AsyncRead + Unpin, W: AsyncWrite + Unpin>(
+    mut read_half: R,
+    mut write_half: W,
 ) -> io::Result<()> {
     const HANDSHAKE: u8 = 0x16;
     const CHANGE_CIPHER_SPEC: u8 = 0x14;
@@ -370,11 +703,14 @@ async fn copy_until_tls12_handshake_finished<'a>(
     Ok(())
 }
 
-async fn relay_until_tls12_handshake_finished(
-    inbound: &mut TcpStream,
+async fn relay_until_tls12_handshake_finished<I: AsyncRead + AsyncWrite + Unpin>(
+    inbound: &mut I,
     outbound: &mut TcpStream,
 ) -> io::Result<()> {
-    let (rin, win) = inbound.split();
+    // `inbound` is split by wrapping reference (tokio::io::split works for any AsyncRead +
+    // AsyncWrite, not just TcpStream), while `outbound` keeps using TcpStream's own zero-copy
+    // `split`.
+    let (rin, win) = tokio_io::split(inbound);
     let (rout, wout) = outbound.split();
     let (a, b) = tokio::join!(
         copy_until_tls12_handshake_finished(rin, wout),
@@ -384,3 +720,95 @@ async fn relay_until_tls12_handshake_finished(
     b?;
     Ok(())
 }
+
+#[cfg(test)]
+mod reassemble_handshake_message_tests {
+    use super::*;
+
+    fn tls_record(content_type: u8, body: &[u8]) -> Vec<u8> {
+        let mut record = vec![content_type, 0x03, 0x03];
+        record.extend_from_slice(&(body.len() as u16).to_be_bytes());
+        record.extend_from_slice(body);
+        record
+    }
+
+    #[tokio::test]
+    async fn reassembles_a_handshake_message_fragmented_across_records() {
+        // handshake header (type=0x01, 3-byte length=6) + 6-byte body, split across two records
+        let hs = [0x01, 0x00, 0x00, 0x06, b'a', b'b', b'c', b'd', b'e', b'f'];
+        let record1 = tls_record(0x16, &hs[..4]);
+        let record2 = tls_record(0x16, &hs[4..]);
+        let mut wire = record1.clone();
+        wire.extend_from_slice(&record2);
+        let mut stream = std::io::Cursor::new(wire);
+
+        let mut raw = Vec::new();
+        let synthetic = reassemble_handshake_message(&mut stream, &mut raw)
+            .await
+            .unwrap()
+            .expect("well-formed fragmented handshake message should reassemble");
+
+        let mut expected_raw = record1;
+        expected_raw.extend_from_slice(&record2);
+        assert_eq!(raw, expected_raw);
+
+        let mut expected_synthetic = vec![0x16, 0x03, 0x03, 0x00, 0x0a];
+        expected_synthetic.extend_from_slice(&hs);
+        assert_eq!(synthetic, expected_synthetic);
+    }
+
+    #[tokio::test]
+    async fn preserves_trailing_coalesced_bytes_in_raw_but_not_in_the_parsed_message() {
+        // one record carrying a complete handshake message plus the start of a second one
+        // coalesced right after it
+        let hs = [0x01, 0x00, 0x00, 0x06, b'a', b'b', b'c', b'd', b'e', b'f'];
+        let trailing = [0x02, 0xaa, 0xbb, 0xcc];
+        let mut body = hs.to_vec();
+        body.extend_from_slice(&trailing);
+        let record = tls_record(0x16, &body);
+        let mut stream = std::io::Cursor::new(record.clone());
+
+        let mut raw = Vec::new();
+        let synthetic = reassemble_handshake_message(&mut stream, &mut raw)
+            .await
+            .unwrap()
+            .expect("well-formed handshake message should reassemble");
+
+        // raw keeps the whole record, coalesced trailing bytes included
+        assert_eq!(raw, record);
+        // the parsed view only contains the handshake message that was waited for
+        let mut expected_synthetic = vec![0x16, 0x03, 0x03, 0x00, 0x0a];
+        expected_synthetic.extend_from_slice(&hs);
+        assert_eq!(synthetic, expected_synthetic);
+    }
+
+    #[tokio::test]
+    async fn preserves_raw_bytes_of_a_non_handshake_record() {
+        // an active prober's first record isn't a TLS handshake record at all; the bytes must
+        // still be preserved in `raw` for accept_or_relay's byte-perfect fallback
+        let record = tls_record(0x15, b"not a handshake");
+        let mut stream = std::io::Cursor::new(record.clone());
+
+        let mut raw = Vec::new();
+        let result = reassemble_handshake_message(&mut stream, &mut raw)
+            .await
+            .unwrap();
+
+        assert!(result.is_err());
+        assert_eq!(raw, record);
+    }
+
+    #[tokio::test]
+    async fn rejects_a_declared_body_length_over_the_cap() {
+        // handshake header declaring an ~16MB body with no intent of ever sending that much
+        let record = tls_record(0x16, &[0x01, 0xff, 0xff, 0xff]);
+        let mut stream = std::io::Cursor::new(record);
+
+        let mut raw = Vec::new();
+        let result = reassemble_handshake_message(&mut stream, &mut raw)
+            .await
+            .unwrap();
+
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,82 @@
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use rustls::client::{ServerCertVerified, ServerCertVerifier, WebPkiVerifier};
+use rustls::{Certificate, Error as TlsError, RootCertStore, ServerName};
+use sha2::{Digest, Sha256};
+
+use crate::utils::NoCertificateVerification;
+
+/// How far the client goes to confirm it is really talking to the intended camouflage front
+/// before committing the Noise session to it.
+///
+/// This only has teeth on the TLS 1.2 path: `tls12_handshake` receives the camouflage server's
+/// real Certificate/CertificateVerify messages in the clear, so the client can actually check
+/// them. On the TLS 1.3 path the handshake is treated as done right after ServerHello and nothing
+/// past it is ever seen by the client, so `Off` is the only meaningful mode there.
+#[derive(Clone)]
+pub enum VerifyMode {
+    /// Accept whatever certificate the camouflage server presents (current/default behavior).
+    Off,
+    /// Verify the full chain against the supplied root store for `server_name`. Only the roots in
+    /// that store are trusted; no system trust anchors are loaded.
+    Chain(Arc<RootCertStore>),
+    /// Accept only a leaf certificate whose SubjectPublicKeyInfo hashes to this SHA-256 digest.
+    Pin([u8; 32]),
+}
+
+impl std::fmt::Debug for VerifyMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VerifyMode::Off => f.write_str("VerifyMode::Off"),
+            VerifyMode::Chain(_) => f.write_str("VerifyMode::Chain(..)"),
+            VerifyMode::Pin(hash) => write!(f, "VerifyMode::Pin({:x?})", hash),
+        }
+    }
+}
+
+impl Default for VerifyMode {
+    fn default() -> Self {
+        VerifyMode::Off
+    }
+}
+
+pub(crate) fn verifier_for(mode: &VerifyMode) -> Arc<dyn ServerCertVerifier> {
+    match mode {
+        VerifyMode::Off => Arc::new(NoCertificateVerification {}),
+        VerifyMode::Chain(roots) => Arc::new(WebPkiVerifier::new((**roots).clone(), None)),
+        VerifyMode::Pin(spki_sha256) => Arc::new(SpkiPinningVerifier {
+            spki_sha256: *spki_sha256,
+        }),
+    }
+}
+
+/// Verifier that ignores chain-of-trust entirely and instead pins the leaf's SPKI hash, for
+/// operators who know exactly which key the front uses and would rather not depend on a CA.
+struct SpkiPinningVerifier {
+    spki_sha256: [u8; 32],
+}
+
+impl ServerCertVerifier for SpkiPinningVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, TlsError> {
+        let (_, cert) = x509_parser::parse_x509_certificate(&end_entity.0).map_err(|e| {
+            TlsError::General(format!("Failed to parse camouflage certificate: {}", e))
+        })?;
+        let digest = Sha256::digest(cert.public_key().raw);
+        if digest.as_slice() == self.spki_sha256 {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(TlsError::General(
+                "Camouflage certificate SPKI does not match the pinned hash".into(),
+            ))
+        }
+    }
+}